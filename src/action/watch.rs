@@ -0,0 +1,196 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! The `watch` action recursively monitors a set of paths for filesystem
+//! changes, replying once per (debounced) event for as long as the request
+//! stays open.
+
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::action;
+use crate::session::{self, Session};
+
+/// Window used to coalesce bursts of events on the same path, unless the
+/// request overrides it.
+const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Number of events after which the watch stops itself, unless the request
+/// overrides it.
+const DEFAULT_EVENT_CAP: u64 = 10_000;
+
+/// Arguments of the `watch` action.
+pub struct Request {
+    /// Paths to recursively monitor for changes.
+    paths: Vec<PathBuf>,
+    /// Window used to coalesce bursts of events on the same path.
+    debounce: Duration,
+    /// Number of events after which the watch stops itself.
+    event_cap: u64,
+}
+
+impl action::Args for Request {
+
+    type Proto = rrg_proto::rrg::WatchRequest;
+
+    fn from_proto(mut proto: rrg_proto::rrg::WatchRequest)
+    -> Result<Request, action::ParseArgsError>
+    {
+        let debounce = if proto.has_debounce_ms() {
+            Duration::from_millis(proto.get_debounce_ms())
+        } else {
+            DEFAULT_DEBOUNCE
+        };
+        let event_cap = if proto.has_event_cap() {
+            proto.get_event_cap()
+        } else {
+            DEFAULT_EVENT_CAP
+        };
+
+        Ok(Request {
+            paths: proto.take_path().into_iter().map(PathBuf::from).collect(),
+            debounce: debounce,
+            event_cap: event_cap,
+        })
+    }
+}
+
+/// The kind of filesystem change that was observed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+    Rename,
+}
+
+impl ChangeKind {
+
+    fn from_notify(event: &notify::DebouncedEvent) -> Option<ChangeKind> {
+        use notify::DebouncedEvent::*;
+
+        match event {
+            Create(_) => Some(ChangeKind::Create),
+            Write(_) | Chmod(_) => Some(ChangeKind::Modify),
+            Remove(_) => Some(ChangeKind::Delete),
+            Rename(_, _) => Some(ChangeKind::Rename),
+            // `NoticeWrite`/`NoticeRemove` precede the real event and
+            // `Rescan`/`Error` carry no single path, so they are not reported.
+            _ => None,
+        }
+    }
+
+    fn into_proto(self) -> rrg_proto::rrg::WatchEvent_ChangeKind {
+        use rrg_proto::rrg::WatchEvent_ChangeKind::*;
+
+        match self {
+            ChangeKind::Create => CREATE,
+            ChangeKind::Modify => MODIFY,
+            ChangeKind::Delete => DELETE,
+            ChangeKind::Rename => RENAME,
+        }
+    }
+}
+
+/// A single coalesced filesystem change.
+pub struct Response {
+    /// Path that changed.
+    path: PathBuf,
+    /// Kind of the observed change.
+    kind: ChangeKind,
+    /// Time at which the change was observed.
+    timestamp: SystemTime,
+}
+
+impl action::Response for Response {
+
+    const RDF_NAME: Option<&'static str> = Some("WatchEvent");
+
+    type Proto = rrg_proto::rrg::WatchEvent;
+
+    fn into_proto(self) -> rrg_proto::rrg::WatchEvent {
+        let timestamp_micros = self.timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_micros() as u64)
+            .unwrap_or(0);
+
+        let mut proto = rrg_proto::rrg::WatchEvent::new();
+        proto.set_path(self.path.to_string_lossy().into_owned());
+        proto.set_kind(self.kind.into_proto());
+        proto.set_timestamp(timestamp_micros);
+
+        proto
+    }
+}
+
+/// Handles the `watch` action, streaming filesystem change events back
+/// through `session` until the event cap is reached or the watch fails.
+pub fn handle<S: Session>(session: &mut S, request: Request) -> session::Result<()> {
+    let (tx, rx) = mpsc::channel();
+
+    let mut watcher: RecommendedWatcher = match Watcher::new(tx, request.debounce) {
+        Ok(watcher) => watcher,
+        Err(error) => {
+            warn!("failed to create a filesystem watcher: {}", error);
+            return Ok(());
+        }
+    };
+
+    let mut watched = 0;
+    for path in &request.paths {
+        match watcher.watch(path, RecursiveMode::Recursive) {
+            Ok(()) => watched += 1,
+            Err(error) => warn!("failed to watch '{}': {}", path.display(), error),
+        }
+    }
+
+    if watched == 0 {
+        warn!("no paths could be watched, not waiting for events");
+        return Ok(());
+    }
+
+    let mut emitted = 0;
+    while emitted < request.event_cap {
+        let event = match rx.recv() {
+            Ok(event) => event,
+            // `watcher` is held alive for the rest of this function and owns
+            // the only sender, so in practice this channel never closes
+            // while we are waiting on it; handled defensively rather than
+            // with `unwrap`/`expect` so a future change to `watcher`'s
+            // lifetime fails safe instead of panicking.
+            Err(_) => break,
+        };
+
+        if let Some(kind) = ChangeKind::from_notify(&event) {
+            let path = notify_event_path(&event);
+
+            session.reply(Response {
+                path: path,
+                kind: kind,
+                timestamp: SystemTime::now(),
+            })?;
+
+            emitted += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Extracts the affected path out of a `notify` event, preferring the
+/// destination path for renames.
+fn notify_event_path(event: &notify::DebouncedEvent) -> PathBuf {
+    use notify::DebouncedEvent::*;
+
+    match event {
+        Create(path) | Write(path) | Chmod(path) | Remove(path) => path.clone(),
+        Rename(_, to) => to.clone(),
+        _ => PathBuf::new(),
+    }
+}
@@ -6,35 +6,69 @@ pub struct Request {
     action: String,
     /// Serialized Protocol Buffers message with request arguments.
     serialized_args: Option<Vec<u8>>,
+    /// Compression scheme `serialized_args` was encoded with, if any.
+    compression: rrg_proto::jobs::GrrMessage_CompressionType,
 }
 
 /// A unique identifier of a request.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct RequestId {
     /// A server-issued session identifier (usually corresponds to a flow).
-    pub(super) session_id: String,
+    pub(crate) session_id: String,
     /// A server-issued request identifier.
-    pub(super) request_id: u64,
+    pub(crate) request_id: u64,
 }
 
 impl Request {
 
+    /// Returns the unique id of this request.
+    pub(crate) fn id(&self) -> &RequestId {
+        &self.id
+    }
+
+    /// Returns the name of the action this request asks to execute.
+    pub(crate) fn action(&self) -> &str {
+        &self.action
+    }
+
     /// Parses the action arguments stored in this request.
     ///
     /// At the moment the request is received we don't know yet what is the type
     /// of the arguments it contains and so we cannot interpret it. Once the
     /// request is dispatched to an appropriate action handler, we can parse the
     /// arguments to a concrete type.
-    pub fn parse_args<A>(&self) -> Result<A, crate::action::ParseArgsError>
+    pub fn parse_args<A>(&self) -> Result<A, crate::session::ParseError>
     where
         A: crate::action::Args,
     {
         let proto_args = match &self.serialized_args {
-            Some(ref bytes) => protobuf::Message::parse_from_bytes(bytes)?,
+            Some(ref bytes) => {
+                let bytes = self.inflate(bytes)
+                    .map_err(crate::session::ParseError::Inflate)?;
+                protobuf::Message::parse_from_bytes(&bytes)
+                    .map_err(crate::session::ParseError::Decode)?
+            }
             None => Default::default(),
         };
 
-        A::from_proto(proto_args)
+        A::from_proto(proto_args).map_err(crate::session::ParseError::Args)
+    }
+
+    /// Inflates `bytes` according to this request's declared compression.
+    fn inflate(&self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        use std::io::Read as _;
+        use rrg_proto::jobs::GrrMessage_CompressionType::*;
+
+        match self.compression {
+            UNCOMPRESSED => Ok(bytes.to_vec()),
+            ZCOMPRESSION => {
+                let mut decoder = flate2::read::ZlibDecoder::new(bytes);
+                let mut inflated = Vec::new();
+                decoder.read_to_end(&mut inflated)?;
+
+                Ok(inflated)
+            }
+        }
     }
 }
 
@@ -66,11 +100,13 @@ impl std::convert::TryFrom<rrg_proto::jobs::GrrMessage> for Request {
         } else {
             None
         };
+        let compression = proto.get_compression();
 
         Ok(Request {
             id: request_id,
             action: action,
             serialized_args,
+            compression,
         })
     }
 }
@@ -4,12 +4,31 @@
 // in the LICENSE file or at https://opensource.org/licenses/MIT.
 
 use std::io::{Read, Write, Result};
+use std::time::{Duration, Instant};
 
 // The same as in the Rust's standard library.
 const DEFAULT_BUF_SIZE: usize = 8 * 1024;
 
-pub fn copy_until<R, W, P>(reader: &mut R, writer: &mut W, mut pred: P)
+pub fn copy_until<R, W, P>(reader: &mut R, writer: &mut W, pred: P)
     -> Result<()>
+where
+    R: Read,
+    W: Write,
+    P: FnMut(&R, &W) -> bool,
+{
+    copy_until_throttled(reader, writer, pred, None)
+}
+
+/// Like [`copy_until`], but throttles reads through `limiter` when one is
+/// given. With `limiter` set to `None` this behaves exactly like
+/// [`copy_until`] — callers that do not care about throughput can ignore
+/// this function entirely.
+pub fn copy_until_throttled<R, W, P>(
+    reader: &mut R,
+    writer: &mut W,
+    mut pred: P,
+    mut limiter: Option<&mut RateLimiter>,
+) -> Result<()>
 where
     R: Read,
     W: Write,
@@ -18,13 +37,27 @@ where
     let mut buf = [0; DEFAULT_BUF_SIZE];
     loop {
         use std::io::ErrorKind::*;
-        let len = match reader.read(&mut buf[..]) {
+
+        // Never ask for more than the limiter's burst size, or a single read
+        // could transfer a full `buf` regardless of how small `capacity` is.
+        let read_len = match limiter {
+            Some(ref mut limiter) => limiter.throttle(buf.len() as u64) as usize,
+            None => buf.len(),
+        };
+
+        let len = match reader.read(&mut buf[..read_len]) {
             Ok(0) => break,
             Ok(len) => len,
             Err(ref error) if error.kind() == Interrupted => continue,
             Err(error) => return Err(error),
         };
 
+        // Only a successful read spends tokens, so retrying after an
+        // `Interrupted` error above re-throttles but never double-charges.
+        if let Some(ref mut limiter) = limiter {
+            limiter.charge(len as u64);
+        }
+
         writer.write_all(&buf[..len])?;
         if pred(reader, writer) {
             break;
@@ -34,6 +67,78 @@ where
     Ok(())
 }
 
+/// A token bucket used to cap the throughput of [`copy_until_throttled`].
+///
+/// The bucket starts full (`capacity` tokens) and refills at `rate` tokens
+/// per second, up to `capacity`. A byte transferred spends one token; when
+/// the bucket holds fewer tokens than a read asks for, callers sleep for
+/// just long enough to cover the shortfall.
+pub struct RateLimiter {
+    /// Maximum number of tokens the bucket can hold (the burst size).
+    capacity: u64,
+    /// Tokens added to the bucket per second.
+    rate: u64,
+    /// Tokens currently available.
+    tokens: f64,
+    /// When the bucket was last refilled.
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+
+    /// Creates a new limiter with burst size `capacity` bytes and a refill
+    /// rate of `rate` bytes per second.
+    pub fn new(capacity: u64, rate: u64) -> RateLimiter {
+        RateLimiter {
+            capacity: capacity,
+            rate: rate,
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Refills the bucket based on the time elapsed since the last refill.
+    fn refill(&mut self) {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        let refilled = self.tokens + elapsed * self.rate as f64;
+
+        self.tokens = refilled.min(self.capacity as f64);
+        self.last_refill = Instant::now();
+    }
+
+    /// Blocks until at least `min(needed, capacity)` tokens are available,
+    /// without spending them (see [`RateLimiter::charge`]), and returns that
+    /// capped amount.
+    ///
+    /// `needed` is capped at `capacity` because the bucket can never hold
+    /// more tokens than that; waiting for the uncapped `needed` would block
+    /// forever once it exceeds `capacity`, and a caller that ignored the cap
+    /// and read `needed` bytes anyway could burst past `capacity` in one go.
+    ///
+    /// With a `rate` of zero the bucket never refills, so this returns the
+    /// capped amount without waiting.
+    fn throttle(&mut self, needed: u64) -> u64 {
+        self.refill();
+
+        let allowed = needed.min(self.capacity);
+
+        let allowed_f = allowed as f64;
+        if self.tokens < allowed_f && self.rate > 0 {
+            let shortfall = allowed_f - self.tokens;
+
+            std::thread::sleep(Duration::from_secs_f64(shortfall / self.rate as f64));
+            self.refill();
+        }
+
+        allowed
+    }
+
+    /// Spends `spent` tokens for bytes that were actually transferred.
+    fn charge(&mut self, spent: u64) {
+        self.tokens = (self.tokens - spent as f64).max(0.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -90,4 +195,63 @@ mod tests {
         assert!(writer.iter().all(|item| *item == 0));
         assert!(writer.len() > limit);
     }
+
+    #[test]
+    fn test_copy_until_throttled_without_limiter_matches_copy_until() {
+        let mut reader: &[u8] = b"foobar";
+        let mut writer = vec!();
+
+        let result = copy_until_throttled(&mut reader, &mut writer, |_, _| false, None);
+
+        assert!(result.is_ok());
+        assert_eq!(writer, b"foobar");
+    }
+
+    #[test]
+    fn test_copy_until_throttled_with_ample_capacity() {
+        let mut reader: &[u8] = b"foobar";
+        let mut writer = vec!();
+        let mut limiter = RateLimiter::new(DEFAULT_BUF_SIZE as u64, DEFAULT_BUF_SIZE as u64);
+
+        let result = copy_until_throttled(&mut reader, &mut writer, |_, _| false, Some(&mut limiter));
+
+        assert!(result.is_ok());
+        assert_eq!(writer, b"foobar");
+    }
+
+    #[test]
+    fn test_copy_until_throttled_never_reads_more_than_capacity() {
+        // A reader that always reports how large a buffer it was asked to
+        // fill, so we can check that a single read is never handed a buffer
+        // bigger than the limiter's burst size.
+        struct RecordingReader {
+            max_requested: usize,
+        }
+
+        impl std::io::Read for RecordingReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.max_requested = self.max_requested.max(buf.len());
+
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+
+                Ok(buf.len())
+            }
+        }
+
+        let mut reader = RecordingReader { max_requested: 0 };
+        let mut writer = vec!();
+        let capacity = 16;
+        // A high refill rate keeps the test fast; only `capacity` (the
+        // burst size) is under test here.
+        let mut limiter = RateLimiter::new(capacity, 1_000_000);
+
+        let result = copy_until_throttled(&mut reader, &mut writer, |_, writer| {
+            writer.len() >= 64
+        }, Some(&mut limiter));
+
+        assert!(result.is_ok());
+        assert!(reader.max_requested as u64 <= capacity);
+    }
 }
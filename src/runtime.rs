@@ -0,0 +1,221 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+//! An event loop that drives the agent.
+//!
+//! Unlike [`session::handle`], which decodes and dispatches a single message
+//! and returns, [`EventLoop`] owns the transport for the lifetime of the
+//! agent. It `poll`s the transport's descriptor so it can interleave
+//! incoming requests with periodic work (heartbeats, deadline checks)
+//! instead of blocking on a read forever.
+//!
+//! [`session::handle`]: crate::session::handle
+
+use std::convert::TryFrom;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use log::{error, warn};
+
+/// How often the loop wakes up, at minimum, to emit a heartbeat and check
+/// for overrun actions.
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(5);
+
+/// How long a single action is allowed to run before it is considered to
+/// have overrun its deadline.
+const DEFAULT_ACTION_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+/// A transport that the event loop can poll and read decoded messages from.
+pub trait Transport: AsRawFd {
+    /// The message type yielded by this transport.
+    type Message;
+
+    /// Returns the next message if one is immediately available, without
+    /// blocking. Called only after `poll` reports the descriptor readable.
+    fn try_recv(&mut self) -> Option<Self::Message>;
+
+    /// Sends a heartbeat/startup message to the server.
+    fn heartbeat(&mut self);
+}
+
+/// Drives `transport`, dispatching messages with `handle` and waking up at
+/// least every `heartbeat` to report liveness and to cancel actions that
+/// have overrun `action_deadline`.
+pub struct EventLoop<T> {
+    transport: T,
+    heartbeat: Duration,
+    action_deadline: Duration,
+}
+
+impl<T> EventLoop<T>
+where
+    T: Transport,
+{
+
+    /// Creates a new event loop with the default heartbeat and action
+    /// deadline.
+    pub fn new(transport: T) -> EventLoop<T> {
+        EventLoop {
+            transport,
+            heartbeat: DEFAULT_HEARTBEAT,
+            action_deadline: DEFAULT_ACTION_DEADLINE,
+        }
+    }
+
+    /// Overrides how often the loop wakes up to emit a heartbeat.
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> EventLoop<T> {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Overrides the wall-clock deadline after which a running action is
+    /// considered to have overrun and is abandoned.
+    pub fn with_action_deadline(mut self, deadline: Duration) -> EventLoop<T> {
+        self.action_deadline = deadline;
+        self
+    }
+
+    /// Runs the loop until `handle` returns `false`, or until polling the
+    /// transport fails.
+    ///
+    /// Every ready message is drained and dispatched before the loop sleeps
+    /// again, so a burst of requests is handled without extra wakeups. Each
+    /// message is dispatched on its own thread; the loop itself waits on it
+    /// in `heartbeat`-sized slices so liveness is still reported while a
+    /// long-running action is in flight, and abandons (but does not await
+    /// further) an action that is still running once `action_deadline`
+    /// elapses.
+    pub fn run<H>(&mut self, handle: H)
+    where
+        H: Fn(T::Message) -> bool + Send + Sync + 'static,
+        T::Message: Send + 'static,
+    {
+        let handle = Arc::new(handle);
+        let mut last_heartbeat = Instant::now();
+
+        'poll: loop {
+            let elapsed = last_heartbeat.elapsed();
+            let timeout = self.heartbeat.checked_sub(elapsed)
+                .unwrap_or(Duration::from_secs(0));
+
+            match poll_readable(self.transport.as_raw_fd(), timeout) {
+                Ok(true) => loop {
+                    match self.transport.try_recv() {
+                        Some(message) => {
+                            let keep_going = self.dispatch(
+                                Arc::clone(&handle),
+                                message,
+                                &mut last_heartbeat,
+                            );
+                            if !keep_going {
+                                break 'poll;
+                            }
+                        }
+                        None => break,
+                    }
+                },
+                Ok(false) => {
+                    // Timed out: nothing to read yet, fall through to the
+                    // heartbeat check below.
+                }
+                Err(error) => {
+                    error!("failed to poll the transport: {}", error);
+                    break 'poll;
+                }
+            }
+
+            if last_heartbeat.elapsed() >= self.heartbeat {
+                self.transport.heartbeat();
+                last_heartbeat = Instant::now();
+            }
+        }
+    }
+
+    /// Runs `handle(message)` on its own thread and waits for it to
+    /// complete, waking up every `self.heartbeat` to emit a heartbeat
+    /// (updating `*last_heartbeat`) for as long as the action keeps running.
+    ///
+    /// If the action is still running once `self.action_deadline` elapses,
+    /// it is warned about and left running rather than awaited further, so
+    /// the caller can keep making progress; in that case there is no result
+    /// to honor, so the loop is conservatively kept alive.
+    fn dispatch<H>(
+        &mut self,
+        handle: Arc<H>,
+        message: T::Message,
+        last_heartbeat: &mut Instant,
+    ) -> bool
+    where
+        H: Fn(T::Message) -> bool + Send + Sync + 'static,
+        T::Message: Send + 'static,
+    {
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let join_handle = std::thread::spawn(move || {
+            // The receiver may already be gone if we overran the deadline;
+            // that is fine, the result is simply dropped.
+            let _ = tx.send(handle(message));
+        });
+
+        let deadline = Instant::now() + self.action_deadline;
+        loop {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                Some(remaining) => remaining,
+                None => {
+                    warn!(
+                        "action overran its {:?} deadline, abandoning it",
+                        self.action_deadline,
+                    );
+                    return true;
+                }
+            };
+            let slice = remaining.min(self.heartbeat);
+
+            match rx.recv_timeout(slice) {
+                Ok(keep_going) => {
+                    let _ = join_handle.join();
+                    return keep_going;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    self.transport.heartbeat();
+                    *last_heartbeat = Instant::now();
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    warn!("action panicked before completing");
+                    return true;
+                }
+            }
+        }
+    }
+}
+
+/// Blocks on `fd` until it becomes readable or `timeout` elapses, returning
+/// whether it is readable. A hangup or error reported on `fd` without
+/// pending input is returned as an error.
+fn poll_readable(fd: RawFd, timeout: Duration) -> std::io::Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+
+    let timeout_ms = i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX);
+
+    // SAFETY: `fds` points to a single, live `pollfd` for the duration of
+    // the call, matching the `nfds` we pass.
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+
+    match ready {
+        -1 => Err(std::io::Error::last_os_error()),
+        0 => Ok(false),
+        _ if fds[0].revents & libc::POLLIN != 0 => Ok(true),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "transport descriptor hung up or reported an error",
+        )),
+    }
+}
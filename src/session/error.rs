@@ -0,0 +1,106 @@
+// Copyright 2020 Google LLC
+//
+// Use of this source code is governed by an MIT-style license that can be found
+// in the LICENSE file or at https://opensource.org/licenses/MIT.
+
+use std::fmt::{Display, Formatter};
+
+/// An error type for failures that can occur while dispatching an action and
+/// sending its response back to the server.
+#[derive(Debug)]
+pub enum Error {
+    /// Failed to encode an outgoing message.
+    Encode(protobuf::ProtobufError),
+    /// Failed to parse an incoming message.
+    Parse(ParseError),
+}
+
+impl Display for Error {
+
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Encode(error) => write!(fmt, "failed to encode message: {}", error),
+            Error::Parse(error) => write!(fmt, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+}
+
+impl From<protobuf::ProtobufError> for Error {
+
+    fn from(error: protobuf::ProtobufError) -> Error {
+        Error::Encode(error)
+    }
+}
+
+impl From<ParseError> for Error {
+
+    fn from(error: ParseError) -> Error {
+        Error::Parse(error)
+    }
+}
+
+/// An error type for failures that can occur when parsing an incoming demand
+/// or its payload.
+#[derive(Debug)]
+pub enum ParseError {
+    /// A required field was missing from the message.
+    MissingField(&'static str),
+    /// The message payload was not a valid Protocol Buffers message.
+    Decode(protobuf::ProtobufError),
+    /// The message declared a compression scheme that could not be inflated.
+    Inflate(std::io::Error),
+    /// The wire message itself could not be parsed into a request.
+    Request(crate::comms::request::ParseRequestError),
+    /// The request's arguments could not be parsed into the action's type.
+    Args(crate::action::ParseArgsError),
+}
+
+impl Display for ParseError {
+
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::MissingField(field) => {
+                write!(fmt, "missing required field: {}", field)
+            }
+            ParseError::Decode(error) => {
+                write!(fmt, "failed to decode message: {}", error)
+            }
+            ParseError::Inflate(error) => {
+                write!(fmt, "failed to inflate compressed payload: {}", error)
+            }
+            ParseError::Request(error) => {
+                write!(fmt, "failed to parse request: {}", error)
+            }
+            ParseError::Args(error) => {
+                write!(fmt, "failed to parse action arguments: {}", error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+}
+
+impl From<protobuf::ProtobufError> for ParseError {
+
+    fn from(error: protobuf::ProtobufError) -> ParseError {
+        ParseError::Decode(error)
+    }
+}
+
+impl From<crate::comms::request::ParseRequestError> for ParseError {
+
+    fn from(error: crate::comms::request::ParseRequestError) -> ParseError {
+        ParseError::Request(error)
+    }
+}
+
+impl From<crate::action::ParseArgsError> for ParseError {
+
+    fn from(error: crate::action::ParseArgsError) -> ParseError {
+        ParseError::Args(error)
+    }
+}
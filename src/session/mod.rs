@@ -6,19 +6,83 @@
 mod error;
 
 use std::convert::{TryFrom, TryInto};
+use std::io::Write as _;
 
 use log::error;
 
 use crate::action;
+use crate::comms::request;
 use crate::message;
 pub use self::error::{Error, ParseError};
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Default size (in bytes) above which outgoing response arguments are
+/// compressed before being sent to the server.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Configuration controlling whether outgoing response arguments should be
+/// compressed before being sent to the server.
+///
+/// Small payloads are left uncompressed, since the zlib framing overhead is
+/// not worth paying for a few bytes of savings.
+#[derive(Clone, Copy, Debug)]
+pub struct Compression {
+    enabled: bool,
+    threshold: usize,
+}
+
+impl Compression {
+
+    /// Compresses arguments whose serialized size exceeds `threshold` bytes.
+    pub const fn enabled(threshold: usize) -> Compression {
+        Compression {
+            enabled: true,
+            threshold: threshold,
+        }
+    }
+
+    /// Never compresses arguments, regardless of their size.
+    pub const fn disabled() -> Compression {
+        Compression {
+            enabled: false,
+            threshold: 0,
+        }
+    }
+}
+
+impl Default for Compression {
+
+    fn default() -> Compression {
+        Compression::enabled(DEFAULT_COMPRESSION_THRESHOLD)
+    }
+}
+
+/// Compresses `data` according to `compression`, returning the (possibly
+/// unchanged) bytes together with the compression scheme that was applied.
+fn deflate(data: Vec<u8>, compression: Compression)
+    -> (Vec<u8>, rrg_proto::jobs::GrrMessage_CompressionType)
+{
+    use rrg_proto::jobs::GrrMessage_CompressionType::*;
+
+    if !compression.enabled || data.len() <= compression.threshold {
+        return (data, UNCOMPRESSED);
+    }
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    match encoder.write_all(&data).and_then(|()| encoder.finish()) {
+        Ok(compressed) => (compressed, ZCOMPRESSION),
+        Err(error) => {
+            error!("failed to compress response arguments: {}", error);
+            (data, UNCOMPRESSED)
+        }
+    }
+}
+
 pub fn execute<S, R, H>(session: &mut S, handler: H, payload: Payload) -> Result<()>
 where
     S: Session,
-    R: action::Request,
+    R: action::Args,
     H: FnOnce(&mut S, R) -> Result<()>,
 {
     handler(session, payload.parse()?)
@@ -37,10 +101,12 @@ where
     };
 
     let mut session = Action::new(demand.header.clone());
+    let compression = session.compression;
     let result = action::dispatch(&demand.action, &mut session, demand.payload);
 
     let status = Status {
         header: demand.header,
+        compression: compression,
         result: result,
     };
 
@@ -91,14 +157,21 @@ impl Session for Adhoc {
 pub struct Action {
     header: Header,
     next_response_id: u64,
+    compression: Compression,
 }
 
 impl Action {
 
     pub fn new(header: Header) -> Action {
+        Action::with_compression(header, Compression::default())
+    }
+
+    /// Creates a new action session with a custom compression setting.
+    pub fn with_compression(header: Header, compression: Compression) -> Action {
         Action {
             header: header,
             next_response_id: 0,
+            compression: compression,
         }
     }
 }
@@ -111,6 +184,7 @@ impl Session for Action {
             request_id: Some(self.header.request_id),
             response_id: Some(self.next_response_id),
             data: response,
+            compression: self.compression,
         }.send()?;
 
         self.next_response_id += 1;
@@ -126,10 +200,13 @@ impl Session for Action {
     }
 }
 
-pub const STARTUP: Sink = Sink { id: "/flows/F:Startup" };
+// Ad-hoc startup messages are small and sent exactly once, so they opt out of
+// compression rather than pay for a zlib header on a handful of bytes.
+pub const STARTUP: Sink = Sink { id: "/flows/F:Startup", compression: Compression::disabled() };
 
 pub struct Sink {
     id: &'static str,
+    compression: Compression,
 }
 
 impl Sink {
@@ -143,6 +220,7 @@ impl Sink {
             request_id: None,
             response_id: None,
             data: response,
+            compression: self.compression,
         }
     }
 }
@@ -153,24 +231,25 @@ pub struct Demand {
     pub payload: Payload,
 }
 
-impl TryFrom<rrg_proto::GrrMessage> for Demand {
+impl TryFrom<rrg_proto::jobs::GrrMessage> for Demand {
 
     type Error = ParseError;
 
-    fn try_from(message: rrg_proto::GrrMessage)
+    fn try_from(proto: rrg_proto::jobs::GrrMessage)
     -> std::result::Result<Demand, ParseError>
     {
-        use ParseError::*;
+        let request = request::Request::try_from(proto)?;
 
         let header = Header {
-            session_id: message.session_id.ok_or(MissingField("session id"))?,
-            request_id: message.request_id.ok_or(MissingField("request id"))?,
+            session_id: request.id().session_id.clone(),
+            request_id: request.id().request_id,
         };
+        let action = request.action().to_string();
 
         Ok(Demand {
-            action: message.name.ok_or(MissingField("action name"))?,
+            action: action,
             header: header,
-            payload: Payload(message.args),
+            payload: Payload(request),
         })
     }
 }
@@ -181,21 +260,22 @@ pub struct Header {
     pub request_id: u64,
 }
 
-#[derive(Debug)]
-pub struct Payload(Option<Vec<u8>>);
+/// The not-yet-interpreted arguments of a [`Demand`], kept in their wire
+/// representation until the action they belong to is known.
+///
+/// Parsing (and, if necessary, decompression) happens in [`Payload::parse`],
+/// delegating to the very same [`request::Request::parse_args`] that the
+/// legacy, request-response comms path uses, so there is exactly one place
+/// where action arguments are decoded off the wire.
+pub struct Payload(request::Request);
 
 impl Payload {
 
     pub fn parse<R>(&self) -> std::result::Result<R, ParseError>
     where
-        R: action::Request,
+        R: action::Args,
     {
-        let proto = match self {
-            Payload(Some(bytes)) => prost::Message::decode(&bytes[..])?,
-            Payload(None) => Default::default(),
-        };
-
-        Ok(R::from_proto(proto))
+        Ok(self.0.parse_args()?)
     }
 }
 
@@ -204,6 +284,7 @@ struct Response<R: action::Response> {
     request_id: Option<u64>,
     response_id: Option<u64>,
     data: R,
+    compression: Compression,
 }
 
 impl<R: action::Response> Response<R> {
@@ -216,61 +297,69 @@ impl<R: action::Response> Response<R> {
     }
 }
 
-impl<R: action::Response> TryInto<rrg_proto::GrrMessage> for Response<R> {
+impl<R: action::Response> TryInto<rrg_proto::jobs::GrrMessage> for Response<R> {
 
-    type Error = prost::EncodeError;
+    type Error = protobuf::ProtobufError;
 
     fn try_into(self)
-    -> std::result::Result<rrg_proto::GrrMessage, prost::EncodeError>
+    -> std::result::Result<rrg_proto::jobs::GrrMessage, protobuf::ProtobufError>
     {
-        let mut data = Vec::new();
-        prost::Message::encode(&self.data.into_proto(), &mut data)?;
-
-        Ok(rrg_proto::GrrMessage {
-            session_id: Some(self.session_id),
-            response_id: self.response_id,
-            request_id: self.request_id,
-            r#type: Some(rrg_proto::grr_message::Type::Message.into()),
-            args_rdf_name: R::RDF_NAME.map(String::from),
-            args: Some(data),
-            ..Default::default()
-        })
+        let data = self.data.into_proto().write_to_bytes()?;
+        let (data, compression) = deflate(data, self.compression);
+
+        let mut message = rrg_proto::jobs::GrrMessage::new();
+        message.set_session_id(self.session_id);
+        if let Some(request_id) = self.request_id {
+            message.set_request_id(request_id);
+        }
+        if let Some(response_id) = self.response_id {
+            message.set_response_id(response_id);
+        }
+        message.set_field_type(rrg_proto::jobs::GrrMessage_Type::MESSAGE);
+        if let Some(rdf_name) = R::RDF_NAME {
+            message.set_args_rdf_name(String::from(rdf_name));
+        }
+        message.set_args(data);
+        message.set_compression(compression);
+
+        Ok(message)
     }
 }
 
 struct Status {
     header: Header,
+    compression: Compression,
     result: Result<()>,
 }
 
-impl TryInto<rrg_proto::GrrMessage> for Status {
+impl TryInto<rrg_proto::jobs::GrrMessage> for Status {
 
-    type Error = prost::EncodeError;
+    type Error = protobuf::ProtobufError;
 
     fn try_into(self)
-    -> std::result::Result<rrg_proto::GrrMessage, prost::EncodeError> {
-        let status = match self.result {
-            Ok(()) => rrg_proto::GrrStatus {
-                status: Some(rrg_proto::grr_status::ReturnedStatus::Ok.into()),
-                ..Default::default()
-            },
-            Err(error) => rrg_proto::GrrStatus {
-                status: Some(rrg_proto::grr_status::ReturnedStatus::GenericError.into()),
-                error_message: Some(error.to_string()),
-                ..Default::default()
-            },
+    -> std::result::Result<rrg_proto::jobs::GrrMessage, protobuf::ProtobufError> {
+        let mut status = rrg_proto::jobs::GrrStatus::new();
+        match self.result {
+            Ok(()) => {
+                status.set_status(rrg_proto::jobs::GrrStatus_ReturnedStatus::OK);
+            }
+            Err(error) => {
+                status.set_status(rrg_proto::jobs::GrrStatus_ReturnedStatus::GENERIC_ERROR);
+                status.set_error_message(error.to_string());
+            }
         };
 
-        let mut data = Vec::new();
-        prost::Message::encode(&status, &mut data)?;
+        let data = status.write_to_bytes()?;
+        let (data, compression) = deflate(data, self.compression);
 
-        Ok(rrg_proto::GrrMessage {
-            session_id: Some(self.header.session_id),
-            response_id: Some(self.header.request_id),
-            r#type: Some(rrg_proto::grr_message::Type::Status.into()),
-            args_rdf_name: Some(String::from("GrrStatus")),
-            args: Some(data),
-            ..Default::default()
-        })
+        let mut message = rrg_proto::jobs::GrrMessage::new();
+        message.set_session_id(self.header.session_id);
+        message.set_response_id(self.header.request_id);
+        message.set_field_type(rrg_proto::jobs::GrrMessage_Type::STATUS);
+        message.set_args_rdf_name(String::from("GrrStatus"));
+        message.set_args(data);
+        message.set_compression(compression);
+
+        Ok(message)
     }
 }